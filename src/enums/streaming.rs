@@ -0,0 +1,31 @@
+use std::fmt;
+
+/// the container/encoding a client's `/stream/swyh.*` response is delivered in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamingFormat {
+    Lpcm,
+    Wav,
+    Rf64,
+    Flac,
+    Opus,
+}
+
+impl fmt::Display for StreamingFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            StreamingFormat::Lpcm => "Lpcm",
+            StreamingFormat::Wav => "Wav",
+            StreamingFormat::Rf64 => "Rf64",
+            StreamingFormat::Flac => "Flac",
+            StreamingFormat::Opus => "Opus",
+        };
+        f.write_str(name)
+    }
+}
+
+/// feedback sent to the UI about a renderer's streaming connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamingState {
+    Started,
+    Ended,
+}
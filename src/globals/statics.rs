@@ -0,0 +1,28 @@
+use crate::{enums::streaming::StreamingFormat, utils::rwstream::ChannelStream};
+use once_cell::sync::Lazy;
+use parking_lot::RwLock as PlRwLock;
+use std::collections::HashMap;
+
+/// renderer-facing configuration, updated from the UI whenever the user changes the
+/// streaming format or bit depth
+#[derive(Debug, Clone, Default)]
+pub struct Configuration {
+    pub streaming_format: Option<StreamingFormat>,
+    pub bits_per_sample: Option<u16>,
+    pub use_wave_format: bool,
+}
+
+pub static CONFIG: Lazy<PlRwLock<Configuration>> =
+    Lazy::new(|| PlRwLock::new(Configuration::default()));
+
+/// streaming clients currently being served, keyed by remote address (or, for WHEP
+/// sessions, by `whep:<session_id>`); the capture thread broadcasts captured samples
+/// to every entry in here
+pub static CLIENTS: Lazy<PlRwLock<HashMap<String, ChannelStream>>> =
+    Lazy::new(|| PlRwLock::new(HashMap::new()));
+
+/// title of the track currently playing, as last reported by the renderer's
+/// now-playing metadata; `IcyMetadataStream` reads this to build ICY `StreamTitle`
+/// blocks. The capture/OpenHome render-control path is responsible for keeping this
+/// up to date via `CURRENT_TRACK_TITLE.write()` whenever the now-playing info changes
+pub static CURRENT_TRACK_TITLE: Lazy<PlRwLock<String>> = Lazy::new(|| PlRwLock::new(String::new()));
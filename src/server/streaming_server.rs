@@ -1,16 +1,138 @@
 use crate::{
     enums::streaming::{
-        StreamingFormat::{Flac, Lpcm, Rf64, Wav},
+        StreamingFormat,
+        StreamingFormat::{Flac, Lpcm, Opus, Rf64, Wav},
         StreamingState,
     },
-    globals::statics::{CLIENTS, CONFIG},
+    globals::statics::{CLIENTS, CONFIG, CURRENT_TRACK_TITLE},
     openhome::rendercontrol::WavData,
-    utils::{rwstream::ChannelStream, ui_logger::ui_log},
+    utils::{
+        rwstream::{resample_stereo, ChannelStream},
+        ui_logger::ui_log,
+    },
 };
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use log::debug;
-use std::{net::IpAddr, sync::Arc};
-use tiny_http::{Header, Method, Response, Server};
+use once_cell::sync::Lazy;
+use parking_lot::RwLock as PlRwLock;
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Read,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tiny_http::{Header, Method, Request, Response, Server};
+// `webrtc` and `tokio` (driving WHEP_RUNTIME below) are new dependencies introduced by
+// this request; this tree has no Cargo.toml to add them to, but they'd need adding
+// there in the real crate
+use webrtc::{
+    api::{media_engine::MediaEngine, APIBuilder},
+    ice_transport::ice_server::RTCIceServer,
+    media::Sample,
+    peer_connection::{
+        configuration::RTCConfiguration, sdp::session_description::RTCSessionDescription,
+        RTCPeerConnection,
+    },
+    rtp_transceiver::rtp_codec::RTCRtpCodecCapability,
+    track::track_local::{track_local_static_sample::TrackLocalStaticSample, TrackLocal},
+};
+
+/// active WHEP (WebRTC-HTTP Egress Protocol) sessions, keyed by the session id handed
+/// back in the `Location` header of the `201 Created` response to `POST /whep`
+static WHEP_SESSIONS: Lazy<PlRwLock<HashMap<String, Arc<RTCPeerConnection>>>> =
+    Lazy::new(|| PlRwLock::new(HashMap::new()));
+
+/// WHEP negotiation drives an async `RTCPeerConnection`; run it on a small dedicated
+/// Tokio runtime so the rest of the (plain-threaded) server doesn't need one
+static WHEP_RUNTIME: Lazy<tokio::runtime::Runtime> =
+    Lazy::new(|| tokio::runtime::Runtime::new().expect("failed to start the WHEP runtime"));
+
+static NEXT_WHEP_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// the ICY metadata interval we advertise in `icy-metaint`, in bytes of audio between
+/// two consecutive metadata blocks - 16KB is the value most clients (and Shoutcast
+/// itself) expect
+const ICY_METAINT: usize = 16384;
+
+/// the ICY metadata length byte is a single `u8` counting 16-byte units, so a block's
+/// payload can never exceed this many bytes
+const ICY_MAX_METADATA_BYTES: usize = 255 * 16;
+/// bytes consumed by the `StreamTitle='';` wrapper around the title itself
+const ICY_TITLE_TAG_OVERHEAD: usize = "StreamTitle='';".len();
+
+/// wraps a streaming `Read` and injects a SHOUTcast/ICY metadata block every
+/// `ICY_METAINT` bytes of audio, for clients that sent `Icy-MetaData: 1`
+///
+/// the block format is a single length byte `L` (the payload length in 16-byte units)
+/// followed by `StreamTitle='<title>';` right-padded with NUL bytes to `L * 16` bytes;
+/// `L == 0` means "title unchanged since the last block"
+struct IcyMetadataStream<R> {
+    inner: R,
+    bytes_until_metadata: usize,
+    last_title: String,
+    pending: VecDeque<u8>,
+}
+
+impl<R: Read> IcyMetadataStream<R> {
+    fn new(inner: R) -> Self {
+        IcyMetadataStream {
+            inner,
+            bytes_until_metadata: ICY_METAINT,
+            last_title: String::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn build_metadata_block(&mut self) -> VecDeque<u8> {
+        let mut title = CURRENT_TRACK_TITLE.read().clone();
+        // the length byte can only express 0-255 sixteen-byte units (max 4080 bytes),
+        // so a title long enough to overflow that must be truncated rather than let
+        // `units as u8` wrap and desync every ICY client's framing for good
+        let max_title_bytes = ICY_MAX_METADATA_BYTES - ICY_TITLE_TAG_OVERHEAD;
+        if title.len() > max_title_bytes {
+            let mut cut = max_title_bytes;
+            while cut > 0 && !title.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            title.truncate(cut);
+        }
+        // compare after truncation, since `last_title` holds the truncated form
+        if title == self.last_title {
+            return VecDeque::from([0u8]);
+        }
+        self.last_title = title.clone();
+        let mut payload = format!("StreamTitle='{title}';").into_bytes();
+        let units = payload.len().div_ceil(16).min(255);
+        payload.resize(units * 16, 0);
+        let mut block = VecDeque::with_capacity(1 + payload.len());
+        block.push_back(units as u8);
+        block.extend(payload);
+        block
+    }
+}
+
+impl<R: Read> Read for IcyMetadataStream<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if !self.pending.is_empty() {
+            let n = self.pending.len().min(buf.len());
+            for slot in buf.iter_mut().take(n) {
+                *slot = self.pending.pop_front().unwrap();
+            }
+            return Ok(n);
+        }
+        let to_read = buf.len().min(self.bytes_until_metadata);
+        let n = self.inner.read(&mut buf[..to_read])?;
+        self.bytes_until_metadata -= n;
+        if self.bytes_until_metadata == 0 {
+            self.pending = self.build_metadata_block();
+            self.bytes_until_metadata = ICY_METAINT;
+        }
+        Ok(n)
+    }
+}
 
 /// streaming state feedback for a client
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -19,6 +141,52 @@ pub struct StreamerFeedBack {
     pub streaming_state: StreamingState,
 }
 
+/// a handle on a running `run_server` instance, used to stop it (e.g. when the GUI
+/// changes capture device/format, or restarts the server on a new port)
+pub struct ServerHandle {
+    server: Arc<Server>,
+    stopped: Arc<AtomicBool>,
+    /// number of threads parked in `server.incoming_requests()`; `unblock()` only
+    /// wakes one blocked thread per call, so shutdown must call it once per thread
+    worker_count: usize,
+}
+
+impl ServerHandle {
+    /// flags the server as stopped and unblocks each accept-loop worker thread, then
+    /// returns without waiting for them. Once the workers have exited, a background
+    /// thread started by `run_server` stops every client still in `CLIENTS` and sends
+    /// `StreamingState::Ended` for each; safe to call more than once
+    pub fn shutdown(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        for _ in 0..self.worker_count {
+            self.server.unblock();
+        }
+    }
+}
+
+/// the subset of an HTTP `Range` request we can satisfy on a live, effectively
+/// infinite stream: an open-ended "start at N" range. Anything bounded (a tail range,
+/// or a range with an end byte) asks for something we can't seek back to and is
+/// rejected with `416 Range Not Satisfiable`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RangeRequest {
+    Open(u64),
+    Unsatisfiable,
+}
+
+/// parse a `Range: bytes=N-` header value, the only form renderers use against us
+fn parse_range_header(value: &str) -> RangeRequest {
+    let Some(spec) = value.strip_prefix("bytes=") else {
+        return RangeRequest::Unsatisfiable;
+    };
+    match spec.split_once('-') {
+        Some((start, "")) => start
+            .parse::<u64>()
+            .map_or(RangeRequest::Unsatisfiable, RangeRequest::Open),
+        _ => RangeRequest::Unsatisfiable,
+    }
+}
+
 /// `run_server` - run a tiny-http webserver to serve streaming requests from renderers
 ///
 /// all music is sent in audio/l16 PCM format (i16) with the sample rate of the source
@@ -30,13 +198,24 @@ pub fn run_server(
     server_port: u16,
     wd: WavData,
     feedback_tx: &Sender<StreamerFeedBack>,
-) {
-    const VALID_URLS: [&str; 4] = [
+) -> ServerHandle {
+    const VALID_URLS: [&str; 5] = [
         "/stream/swyh.wav",
         "/stream/swyh.raw",
         "/stream/swyh.flac",
         "/stream/swyh.rf64",
+        "/stream/swyh.opus",
     ];
+    // the synthetic "infinite" total size we advertise for the live stream, matching
+    // the streamsize/chunksize sentinels used further down (u32::MAX for WAV, as its
+    // length fields are 32 bits, i64::MAX for everything else)
+    let total_size = |format: StreamingFormat| -> u64 {
+        if format == Wav {
+            u32::MAX as u64
+        } else {
+            i64::MAX as u64
+        }
+    };
     let addr = format!("{local_addr}:{server_port}");
     ui_log(&format!(
         "The streaming server is listening on http://{addr}/stream/swyh.wav"
@@ -51,14 +230,22 @@ pub fn run_server(
         )
     };
     ui_log(&logmsg);
+    const WORKER_THREADS: usize = 2;
     let server = Arc::new(Server::http(addr).unwrap());
+    let stopped = Arc::new(AtomicBool::new(false));
     let mut handles = Vec::new();
     // always have two threads ready to serve new requests
-    for _ in 0..2 {
+    for _ in 0..WORKER_THREADS {
         let server = server.clone();
         let feedback_tx_c = feedback_tx.clone();
+        let stopped = stopped.clone();
         handles.push(std::thread::spawn(move || {
             for rq in server.incoming_requests() {
+                // `server.unblock()` makes `incoming_requests()` yield once with no
+                // request available to serve; bail out instead of handling garbage
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
                 let feedback_tx_c = feedback_tx_c.clone();
                 // start streaming in a new thread and continue serving new requests
                 std::thread::spawn(move || {
@@ -82,9 +269,24 @@ pub fn run_server(
                         Header::from_bytes(&b"Server"[..], &b"swyh-rs tiny-http"[..]).unwrap();
                     let nm_hdr = Header::from_bytes(&b"icy-name"[..], &b"swyh-rs"[..]).unwrap();
                     let cc_hdr = Header::from_bytes(&b"Connection"[..], &b"close"[..]).unwrap();
-                    // don't accept range headers (Linn) until I know how to handle them
+                    // we now honor open-ended byte ranges (e.g. Linn renderers probing
+                    // with "Range: bytes=0-" before committing to a stream)
                     let acc_rng_hdr =
-                        Header::from_bytes(&b"Accept-Ranges"[..], &b"none"[..]).unwrap();
+                        Header::from_bytes(&b"Accept-Ranges"[..], &b"bytes"[..]).unwrap();
+                    let range_rq = rq
+                        .headers()
+                        .iter()
+                        .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case("Range"))
+                        .map(|h| parse_range_header(h.value.as_str()));
+                    // SHOUTcast/ICY clients (and some media players) ask for interleaved
+                    // now-playing metadata by sending this header
+                    let icy_requested = rq.headers().iter().any(|h| {
+                        h.field
+                            .as_str()
+                            .as_str()
+                            .eq_ignore_ascii_case("Icy-MetaData")
+                            && h.value.as_str() == "1"
+                    });
                     // check url
                     if !VALID_URLS.contains(&rq.url()) {
                         ui_log(&format!(
@@ -122,6 +324,7 @@ pub fn run_server(
                                 Some("wav") => (16, Wav),
                                 Some("rf64") => (16, Rf64),
                                 Some("raw") => (16, Lpcm),
+                                Some("opus") => (16, Opus),
                                 None | Some(&_) => (bps, format),
                             }
                         } else {
@@ -138,6 +341,8 @@ pub fn run_server(
                         "audio/flac".to_string()
                     } else if format == Wav || format == Rf64 {
                         "audio/vnd.wave;codec=1".to_string()
+                    } else if format == Opus {
+                        "audio/ogg; codecs=opus".to_string()
                     } else {
                         // LPCM
                         if bps == 16 {
@@ -153,6 +358,48 @@ pub fn run_server(
                             .unwrap();
                     // handle response, streaming if GET, headers only otherwise
                     if matches!(rq.method(), Method::Get) {
+                        // we can satisfy an open-ended "bytes=N-" range by skipping the
+                        // stream forward to N, but a bounded or tail range asks for bytes
+                        // of a finite, seekable resource that this live stream isn't.
+                        // Flac/Opus responses are encoded container bytes (FLAC frames /
+                        // Ogg pages), so skipping to an arbitrary byte offset would land
+                        // mid-frame and hand the client a corrupt stream - only raw PCM
+                        // formats can honor a nonzero start offset ("bytes=0-" is
+                        // accepted for every format)
+                        let requests_encoded_format_skip = matches!(format, Flac | Opus)
+                            && matches!(range_rq, Some(RangeRequest::Open(offset)) if offset > 0);
+                        if matches!(range_rq, Some(RangeRequest::Unsatisfiable))
+                            || requests_encoded_format_skip
+                        {
+                            ui_log(&format!(
+                                "Rejecting Range request from {remote_addr}: {}",
+                                if requests_encoded_format_skip {
+                                    format!("{format} streams can't be seeked mid-container")
+                                } else {
+                                    "unsatisfiable range".to_string()
+                                }
+                            ));
+                            let cr_hdr = Header::from_bytes(
+                                &b"Content-Range"[..],
+                                format!("bytes */{}", total_size(format)).as_bytes(),
+                            )
+                            .unwrap();
+                            let response = Response::empty(416)
+                                .with_header(cc_hdr)
+                                .with_header(srvr_hdr)
+                                .with_header(nm_hdr)
+                                .with_header(cr_hdr);
+                            if let Err(e) = rq.respond(response) {
+                                ui_log(&format!(
+                                    "=>Http streaming request with {remote_addr} terminated [{e}]"
+                                ));
+                            }
+                            return;
+                        }
+                        let start_offset = match range_rq {
+                            Some(RangeRequest::Open(offset)) => offset,
+                            _ => 0,
+                        };
                         ui_log(&format!(
                             "Received request {} from {}",
                             rq.url(),
@@ -167,6 +414,7 @@ pub fn run_server(
                             wd.sample_rate.0,
                             bps,
                             format,
+                            start_offset,
                         );
                         let nclients = {
                             let mut clients = CLIENTS.write();
@@ -184,6 +432,7 @@ pub fn run_server(
                         let streaming_format = match format {
                             Flac => "audio/FLAC",
                             Wav | Rf64 => "audio/wave;codec=1 (WAV)",
+                            Opus => "audio/ogg;codecs=opus (Opus)",
                             Lpcm => {
                                 if bps == 16 {
                                     "audio/L16 (LPCM)"
@@ -205,8 +454,14 @@ pub fn run_server(
                         } else {
                             (Some((i64::MAX - 1) as usize), i64::MAX as usize)
                         };
-                        let response = Response::empty(200)
-                            .with_data(channel_stream, streamsize)
+                        let data: Box<dyn Read + Send> = if icy_requested {
+                            Box::new(IcyMetadataStream::new(channel_stream))
+                        } else {
+                            Box::new(channel_stream)
+                        };
+                        let status_code = if range_rq.is_some() { 206 } else { 200 };
+                        let mut response = Response::empty(status_code)
+                            .with_data(data, streamsize)
                             .with_chunked_threshold(chunksize)
                             .with_header(cc_hdr)
                             .with_header(ct_hdr)
@@ -214,6 +469,23 @@ pub fn run_server(
                             .with_header(srvr_hdr)
                             .with_header(acc_rng_hdr)
                             .with_header(nm_hdr);
+                        if icy_requested {
+                            let metaint_hdr = Header::from_bytes(
+                                &b"icy-metaint"[..],
+                                ICY_METAINT.to_string().as_bytes(),
+                            )
+                            .unwrap();
+                            response = response.with_header(metaint_hdr);
+                        }
+                        if range_rq.is_some() {
+                            let total = total_size(format);
+                            let cr_hdr = Header::from_bytes(
+                                &b"Content-Range"[..],
+                                format!("bytes {start_offset}-{}/{total}", total - 1).as_bytes(),
+                            )
+                            .unwrap();
+                            response = response.with_header(cr_hdr);
+                        }
                         if cfg!(debug_assertions) {
                             debug!("==> Response:");
                             debug!(
@@ -233,7 +505,9 @@ pub fn run_server(
                         let nclients = {
                             let mut clients = CLIENTS.write();
                             if let Some(chs) = clients.remove(&remote_addr) {
-                                chs.stop_flac_encoder();
+                                // generalized to flush/close whichever encoder (FLAC or
+                                // Opus) this client's stream was using
+                                chs.stop_encoder();
                             };
                             clients.len()
                         };
@@ -264,14 +538,34 @@ pub fn run_server(
                         }
                     } else if matches!(rq.method(), Method::Post) {
                         debug!("POST rq from {}", remote_addr);
-                        let response = Response::empty(200)
-                            .with_header(cc_hdr)
-                            .with_header(srvr_hdr)
-                            .with_header(nm_hdr);
-                        if let Err(e) = rq.respond(response) {
-                            ui_log(&format!(
-                                "=>Http POST connection with {remote_addr} terminated [{e}]"
-                            ));
+                        if rq.url() == "/whep" {
+                            handle_whep_offer(rq, wd.sample_rate.0, cc_hdr, srvr_hdr, nm_hdr);
+                        } else {
+                            let response = Response::empty(200)
+                                .with_header(cc_hdr)
+                                .with_header(srvr_hdr)
+                                .with_header(nm_hdr);
+                            if let Err(e) = rq.respond(response) {
+                                ui_log(&format!(
+                                    "=>Http POST connection with {remote_addr} terminated [{e}]"
+                                ));
+                            }
+                        }
+                    } else if matches!(rq.method(), Method::Delete) {
+                        debug!("DELETE rq from {}", remote_addr);
+                        let whep_session = rq.url().strip_prefix("/whep/").map(str::to_string);
+                        if let Some(session_id) = whep_session {
+                            handle_whep_teardown(rq, &session_id, cc_hdr, srvr_hdr, nm_hdr);
+                        } else {
+                            let response = Response::empty(404)
+                                .with_header(cc_hdr)
+                                .with_header(srvr_hdr)
+                                .with_header(nm_hdr);
+                            if let Err(e) = rq.respond(response) {
+                                ui_log(&format!(
+                                    "=>Http DELETE connection with {remote_addr} terminated [{e}]"
+                                ));
+                            }
                         }
                     }
                 });
@@ -279,7 +573,274 @@ pub fn run_server(
         }));
     }
 
-    for h in handles {
-        h.join().unwrap();
+    // reap the accept-loop threads in the background and, once they've drained,
+    // stop any clients still streaming so the GUI can safely reconfigure or exit
+    // without renderers being stuck on a dead connection
+    let feedback_tx = feedback_tx.clone();
+    std::thread::spawn(move || {
+        for h in handles {
+            let _ = h.join();
+        }
+        let mut clients = CLIENTS.write();
+        for (_, chs) in clients.drain() {
+            let remote_ip = chs.remote_ip();
+            chs.stop_encoder();
+            chs.stop();
+            let _ = feedback_tx.send(StreamerFeedBack {
+                remote_ip,
+                streaming_state: StreamingState::Ended,
+            });
+        }
+    });
+
+    ServerHandle {
+        server,
+        stopped,
+        worker_count: WORKER_THREADS,
+    }
+}
+
+/// `POST /whep` - WHEP (WebRTC-HTTP Egress Protocol) signaling endpoint: accepts an
+/// SDP offer, negotiates an `RTCPeerConnection` carrying a single Opus audio track fed
+/// from the live capture, and answers `201 Created` with the SDP answer and a
+/// `Location` header identifying the session (torn down later via `DELETE` on that
+/// same location)
+fn handle_whep_offer(
+    mut rq: Request,
+    sample_rate: u32,
+    cc_hdr: Header,
+    srvr_hdr: Header,
+    nm_hdr: Header,
+) {
+    let mut offer_sdp = String::new();
+    if let Err(e) = rq.as_reader().read_to_string(&mut offer_sdp) {
+        ui_log(&format!("Failed to read WHEP offer body: {e}"));
+        let response = Response::empty(400)
+            .with_header(cc_hdr)
+            .with_header(srvr_hdr)
+            .with_header(nm_hdr);
+        let _ = rq.respond(response);
+        return;
+    }
+    match WHEP_RUNTIME.block_on(negotiate_whep_session(offer_sdp, sample_rate)) {
+        Ok((session_id, answer_sdp)) => {
+            let loc_hdr =
+                Header::from_bytes(&b"Location"[..], format!("/whep/{session_id}").as_bytes())
+                    .unwrap();
+            let ct_hdr = Header::from_bytes(&b"Content-Type"[..], &b"application/sdp"[..]).unwrap();
+            let response = Response::from_string(answer_sdp)
+                .with_status_code(201)
+                .with_header(cc_hdr)
+                .with_header(srvr_hdr)
+                .with_header(nm_hdr)
+                .with_header(loc_hdr)
+                .with_header(ct_hdr);
+            if let Err(e) = rq.respond(response) {
+                ui_log(&format!("=>WHEP negotiation response failed [{e}]"));
+            }
+        }
+        Err(e) => {
+            ui_log(&format!("WHEP negotiation failed: {e}"));
+            let response = Response::empty(500)
+                .with_header(cc_hdr)
+                .with_header(srvr_hdr)
+                .with_header(nm_hdr);
+            let _ = rq.respond(response);
+        }
+    }
+}
+
+/// negotiates a single WHEP session: builds the peer connection, attaches the Opus
+/// track, completes the offer/answer exchange and registers the session for teardown
+async fn negotiate_whep_session(
+    offer_sdp: String,
+    sample_rate: u32,
+) -> webrtc::error::Result<(String, String)> {
+    let mut media_engine = MediaEngine::default();
+    media_engine.register_default_codecs()?;
+    let api = APIBuilder::new().with_media_engine(media_engine).build();
+    let config = RTCConfiguration {
+        ice_servers: vec![RTCIceServer {
+            urls: vec!["stun:stun.l.google.com:19302".to_owned()],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let pc = Arc::new(api.new_peer_connection(config).await?);
+
+    let track = Arc::new(TrackLocalStaticSample::new(
+        RTCRtpCodecCapability {
+            mime_type: webrtc::api::media_engine::MIME_TYPE_OPUS.to_owned(),
+            clock_rate: 48_000,
+            channels: 2,
+            ..Default::default()
+        },
+        "audio".to_owned(),
+        "swyh-rs".to_owned(),
+    ));
+    pc.add_track(track.clone() as Arc<dyn TrackLocal + Send + Sync>)
+        .await?;
+
+    let session_id = NEXT_WHEP_SESSION_ID
+        .fetch_add(1, Ordering::SeqCst)
+        .to_string();
+    spawn_whep_opus_feeder(track, sample_rate, session_id.clone());
+
+    let offer = RTCSessionDescription::offer(offer_sdp)?;
+    pc.set_remote_description(offer).await?;
+    let answer = pc.create_answer(None).await?;
+    let mut gathering_complete = pc.gathering_complete_promise().await;
+    pc.set_local_description(answer).await?;
+    let _ = gathering_complete.recv().await;
+    let local_desc = pc
+        .local_description()
+        .await
+        .ok_or(webrtc::Error::ErrSessionDescriptionMissing)?;
+
+    WHEP_SESSIONS.write().insert(session_id.clone(), pc);
+    Ok((session_id, local_desc.sdp))
+}
+
+/// the `CLIENTS` key a WHEP session's synthetic `ChannelStream` registers under, so
+/// the capture thread broadcasts real samples to it exactly like it does for any
+/// DLNA/OpenHome renderer
+fn whep_client_key(session_id: &str) -> String {
+    format!("whep:{session_id}")
+}
+
+const WHEP_OPUS_FRAME_SAMPLES_PER_CHANNEL: usize = 960; // 20ms @ 48kHz
+
+/// registers a `ChannelStream` under `whep_client_key` so the capture thread's
+/// broadcast reaches this session like any other client, then resamples the raw f32
+/// samples it receives to 48kHz stereo, Opus-encodes them into 20ms packets and pushes
+/// each one onto `track` via `TrackLocalStaticSample::write_sample`. Exits as soon as
+/// the capture channel disconnects, which happens the moment `handle_whep_teardown`
+/// drops this session's `ChannelStream` out of `CLIENTS`
+fn spawn_whep_opus_feeder(
+    track: Arc<TrackLocalStaticSample>,
+    sample_rate: u32,
+    session_id: String,
+) {
+    let client_key = whep_client_key(&session_id);
+    let (tx, rx) = unbounded();
+    let channel_stream =
+        ChannelStream::new(tx, rx, client_key.clone(), false, sample_rate, 16, Lpcm, 0);
+    let raw_rx = channel_stream.raw_receiver();
+    CLIENTS.write().insert(client_key, channel_stream);
+
+    std::thread::spawn(move || {
+        let mut encoder =
+            match opus::Encoder::new(48_000, opus::Channels::Stereo, opus::Application::Audio) {
+                Ok(encoder) => encoder,
+                Err(e) => {
+                    ui_log(&format!("Failed to start WHEP Opus encoder: {e}"));
+                    return;
+                }
+            };
+        let needed = WHEP_OPUS_FRAME_SAMPLES_PER_CHANNEL * 2;
+        let mut carry: Vec<f32> = Vec::new();
+        while let Ok(samples) = raw_rx.recv() {
+            carry.extend(resample_stereo(&samples, sample_rate, 48_000));
+            let mut offset = 0;
+            while carry.len() - offset >= needed {
+                let frame = &carry[offset..offset + needed];
+                offset += needed;
+                let packet = match encoder.encode_vec_float(frame, 4000) {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        ui_log(&format!("WHEP Opus encode failed: {e}"));
+                        continue;
+                    }
+                };
+                let sample = Sample {
+                    data: packet.into(),
+                    duration: std::time::Duration::from_millis(20),
+                    ..Default::default()
+                };
+                if WHEP_RUNTIME.block_on(track.write_sample(&sample)).is_err() {
+                    return;
+                }
+            }
+            carry.drain(..offset);
+        }
+    });
+}
+
+/// `DELETE /whep/<session_id>` - tears down a previously negotiated WHEP session
+fn handle_whep_teardown(
+    rq: Request,
+    session_id: &str,
+    cc_hdr: Header,
+    srvr_hdr: Header,
+    nm_hdr: Header,
+) {
+    let pc = WHEP_SESSIONS.write().remove(session_id);
+    let status_code = if let Some(pc) = pc {
+        if let Some(chs) = CLIENTS.write().remove(&whep_client_key(session_id)) {
+            chs.stop();
+        }
+        WHEP_RUNTIME.block_on(async {
+            if let Err(e) = pc.close().await {
+                ui_log(&format!("Error closing WHEP session {session_id}: {e}"));
+            }
+        });
+        200
+    } else {
+        404
+    };
+    let response = Response::empty(status_code)
+        .with_header(cc_hdr)
+        .with_header(srvr_hdr)
+        .with_header(nm_hdr);
+    if let Err(e) = rq.respond(response) {
+        ui_log(&format!("=>WHEP teardown response failed [{e}]"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_range_header_accepts_open_ended_byte_ranges() {
+        assert_eq!(parse_range_header("bytes=1234-"), RangeRequest::Open(1234));
+        assert_eq!(parse_range_header("bytes=0-"), RangeRequest::Open(0));
+    }
+
+    #[test]
+    fn parse_range_header_rejects_anything_else() {
+        assert_eq!(
+            parse_range_header("bytes=0-499"),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            parse_range_header("bytes=-500"),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(parse_range_header("items=0-"), RangeRequest::Unsatisfiable);
+        assert_eq!(
+            parse_range_header("bytes=abc-"),
+            RangeRequest::Unsatisfiable
+        );
+    }
+
+    // runs both cases in one test function since they share the `CURRENT_TRACK_TITLE`
+    // global and cargo runs tests in parallel by default
+    #[test]
+    fn build_metadata_block_truncates_overflowing_titles_and_dedupes_unchanged_ones() {
+        let huge_title = "x".repeat(5000);
+        *CURRENT_TRACK_TITLE.write() = huge_title;
+        let mut stream = IcyMetadataStream::new(std::io::empty());
+        let block = stream.build_metadata_block();
+        let units = block[0] as usize;
+        assert!(units <= 255);
+        assert_eq!(block.len() - 1, units * 16);
+
+        let unchanged = stream.build_metadata_block();
+        assert_eq!(unchanged, VecDeque::from([0u8]));
+
+        *CURRENT_TRACK_TITLE.write() = "A Shorter Title".to_string();
+        let changed = stream.build_metadata_block();
+        assert_ne!(changed, VecDeque::from([0u8]));
     }
 }
@@ -0,0 +1,435 @@
+//! `ChannelStream` is the `Read` implementation backing one HTTP client's response
+//! body. It owns the receiving end of the crossbeam channel the capture/`wave_reader`
+//! thread broadcasts captured f32 PCM samples on, and turns those samples into bytes
+//! in whatever `StreamingFormat` the client asked for.
+//!
+//! State that has to be visible to both the copy held by the streaming thread (which
+//! reads) and the copy held in `CLIENTS` (which the server pokes on disconnect and
+//! shutdown) lives behind a shared `Mutex`, which is what makes `ChannelStream`
+//! cheaply `Clone`.
+
+use crate::{enums::streaming::StreamingFormat, utils::flac_encoder::FlacEncoder};
+use crossbeam_channel::{Receiver, Sender};
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+    sync::{Arc, Mutex},
+};
+
+/// Ogg Opus always carries 48kHz stereo, 20ms frames, regardless of the source rate
+const OPUS_SAMPLE_RATE: u32 = 48_000;
+const OPUS_CHANNELS: usize = 2;
+const OPUS_FRAME_MS: u32 = 20;
+const OPUS_FRAME_SAMPLES_PER_CHANNEL: usize =
+    (OPUS_SAMPLE_RATE as usize / 1000) * OPUS_FRAME_MS as usize;
+
+struct SharedState {
+    /// bytes already produced (encoded or raw, depending on format) and waiting to be
+    /// handed to the HTTP response
+    out_buf: VecDeque<u8>,
+    /// bytes still to discard before the client sees anything, to honor an
+    /// open-ended `Range: bytes=N-` request
+    skip_remaining: u64,
+    stopped: bool,
+    flac_encoder: Option<FlacEncoder>,
+    opus: Option<OpusPipeline>,
+}
+
+/// a cloneable `Read` source streaming one client's audio, fed by the crossbeam
+/// channel the capture thread sends f32 samples on
+#[derive(Clone)]
+pub struct ChannelStream {
+    rx: Receiver<Vec<f32>>,
+    tx: Sender<Vec<f32>>,
+    remote_ip: String,
+    use_wave_format: bool,
+    bits_per_sample: u16,
+    format: StreamingFormat,
+    state: Arc<Mutex<SharedState>>,
+}
+
+impl ChannelStream {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        tx: Sender<Vec<f32>>,
+        rx: Receiver<Vec<f32>>,
+        remote_ip: String,
+        use_wave_format: bool,
+        sample_rate: u32,
+        bits_per_sample: u16,
+        format: StreamingFormat,
+        start_offset: u64,
+    ) -> Self {
+        ChannelStream {
+            rx,
+            tx,
+            remote_ip,
+            use_wave_format,
+            bits_per_sample,
+            format,
+            state: Arc::new(Mutex::new(SharedState {
+                out_buf: VecDeque::new(),
+                skip_remaining: start_offset,
+                stopped: false,
+                flac_encoder: (format == StreamingFormat::Flac).then(FlacEncoder::new),
+                opus: (format == StreamingFormat::Opus)
+                    .then(|| OpusPipeline::new(sample_rate))
+                    .flatten(),
+            })),
+        }
+    }
+
+    pub fn remote_ip(&self) -> String {
+        self.remote_ip.clone()
+    }
+
+    /// forwards a batch of captured samples to this client; used by the capture side
+    /// to broadcast to every registered `ChannelStream`, DLNA or otherwise
+    pub(crate) fn send_samples(&self, samples: Vec<f32>) -> bool {
+        self.tx.send(samples).is_ok()
+    }
+
+    /// hands out a clone of this stream's receiver, for callers that need the raw f32
+    /// samples rather than this stream's own encoded bytes (e.g. the WHEP feeder,
+    /// which Opus-encodes into RTP packets instead of into `out_buf`). The clone
+    /// shares one queue with this stream: each batch is delivered to only one of the
+    /// two receivers
+    pub(crate) fn raw_receiver(&self) -> Receiver<Vec<f32>> {
+        self.rx.clone()
+    }
+
+    /// flushes/closes whichever encoder this stream was using, if any. Generalized
+    /// from the original FLAC-only cleanup now that Opus needs the same treatment on
+    /// disconnect (it must emit a final Ogg "end of stream" page)
+    pub fn stop_encoder(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(enc) = state.flac_encoder.take() {
+            enc.finish();
+        }
+        if let Some(mut opus) = state.opus.take() {
+            opus.finish(&mut state.out_buf);
+        }
+    }
+
+    /// marks this stream as stopped so the next `read()` returns EOF, used when the
+    /// server shuts down or reconfigures and needs to kick active clients
+    pub fn stop(&self) {
+        self.state.lock().unwrap().stopped = true;
+    }
+
+    /// encodes one batch of captured f32 samples into `state.out_buf`, in whatever
+    /// format this stream was opened with
+    fn encode_into_buffer(&self, state: &mut SharedState, samples: &[f32]) {
+        match self.format {
+            StreamingFormat::Flac => {
+                if let Some(enc) = state.flac_encoder.as_mut() {
+                    state.out_buf.extend(enc.encode(samples));
+                }
+            }
+            StreamingFormat::Opus => {
+                if let Some(opus) = state.opus.as_mut() {
+                    opus.push_samples(samples, &mut state.out_buf);
+                }
+            }
+            // Lpcm / Wav / Rf64: raw interleaved PCM, at the configured bit depth
+            _ => {
+                for &s in samples {
+                    if self.bits_per_sample == 24 {
+                        state.out_buf.extend(f32_to_i24_le(s));
+                    } else {
+                        state.out_buf.extend(f32_to_i16_le(s));
+                    }
+                }
+            }
+        }
+        // honor a pending Range-request skip by discarding from the front of the
+        // buffer before the client ever sees these bytes
+        if state.skip_remaining > 0 {
+            let n = (state.skip_remaining as usize).min(state.out_buf.len());
+            state.out_buf.drain(..n);
+            state.skip_remaining -= n as u64;
+        }
+        // `use_wave_format` is not used: no WAV/RF64 header is written, here or by the
+        // caller, so Wav/Rf64 bodies are the same raw PCM as Lpcm
+        let _ = self.use_wave_format;
+    }
+}
+
+impl Read for ChannelStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.stopped {
+            return Ok(0);
+        }
+        while state.out_buf.is_empty() {
+            drop(state);
+            let Ok(samples) = self.rx.recv() else {
+                return Ok(0);
+            };
+            state = self.state.lock().unwrap();
+            if state.stopped {
+                return Ok(0);
+            }
+            self.encode_into_buffer(&mut state, &samples);
+        }
+        let n = state.out_buf.len().min(buf.len());
+        for slot in buf.iter_mut().take(n) {
+            *slot = state.out_buf.pop_front().unwrap();
+        }
+        Ok(n)
+    }
+}
+
+fn f32_to_i16_le(sample: f32) -> [u8; 2] {
+    let clamped = sample.clamp(-1.0, 1.0);
+    ((clamped * i16::MAX as f32) as i16).to_le_bytes()
+}
+
+fn f32_to_i24_le(sample: f32) -> [u8; 3] {
+    let clamped = sample.clamp(-1.0, 1.0);
+    let v = (clamped * 8_388_607.0) as i32;
+    let b = v.to_le_bytes();
+    [b[0], b[1], b[2]]
+}
+
+/// resamples captured f32 PCM to 48kHz stereo, encodes it to Opus in 20ms frames and
+/// wraps the result in Ogg pages, for the `/stream/swyh.opus` format
+struct OpusPipeline {
+    encoder: opus::Encoder,
+    source_rate: u32,
+    /// interleaved stereo samples at `source_rate`, awaiting enough data to produce
+    /// the next resampled 20ms Opus frame
+    carry: Vec<f32>,
+    serial: u32,
+    sequence: u32,
+    granule_pos: i64,
+    headers_sent: bool,
+}
+
+impl OpusPipeline {
+    fn new(source_rate: u32) -> Option<Self> {
+        let encoder = opus::Encoder::new(
+            OPUS_SAMPLE_RATE,
+            opus::Channels::Stereo,
+            opus::Application::Audio,
+        )
+        .ok()?;
+        Some(OpusPipeline {
+            encoder,
+            source_rate,
+            carry: Vec::new(),
+            serial: stream_serial(),
+            sequence: 0,
+            granule_pos: 0,
+            headers_sent: false,
+        })
+    }
+
+    fn push_samples(&mut self, samples: &[f32], out: &mut VecDeque<u8>) {
+        if !self.headers_sent {
+            self.write_header_pages(out);
+            self.headers_sent = true;
+        }
+        self.carry.extend_from_slice(samples);
+        let resampled = resample_stereo(&self.carry, self.source_rate, OPUS_SAMPLE_RATE);
+        let needed = OPUS_FRAME_SAMPLES_PER_CHANNEL * OPUS_CHANNELS;
+        let mut offset = 0;
+        let mut consumed_out_frames = 0usize;
+        while resampled.len() - offset >= needed {
+            let frame = &resampled[offset..offset + needed];
+            if let Ok(packet) = self.encoder.encode_vec_float(frame, 4000) {
+                self.granule_pos += OPUS_FRAME_SAMPLES_PER_CHANNEL as i64;
+                self.sequence += 1;
+                write_ogg_page(
+                    out,
+                    self.serial,
+                    self.sequence,
+                    self.granule_pos,
+                    0,
+                    &packet,
+                );
+            }
+            offset += needed;
+            consumed_out_frames += OPUS_FRAME_SAMPLES_PER_CHANNEL;
+        }
+        // drop only the source-rate samples that actually went into an encoded
+        // frame, keeping the remainder as carry for the next batch
+        if consumed_out_frames > 0 {
+            let consumed_in_frames = (consumed_out_frames as u64 * self.source_rate as u64
+                / OPUS_SAMPLE_RATE as u64) as usize;
+            let consumed_in_samples = (consumed_in_frames * OPUS_CHANNELS).min(self.carry.len());
+            self.carry.drain(..consumed_in_samples);
+        }
+    }
+
+    fn write_header_pages(&mut self, out: &mut VecDeque<u8>) {
+        let opus_head = build_opus_head();
+        write_ogg_page(out, self.serial, 0, 0, 0x02 /* bos */, &opus_head);
+        self.sequence += 1;
+        let opus_tags = build_opus_tags();
+        write_ogg_page(out, self.serial, self.sequence, 0, 0, &opus_tags);
+        self.sequence += 1;
+    }
+
+    fn finish(&mut self, out: &mut VecDeque<u8>) {
+        self.sequence += 1;
+        write_ogg_page(
+            out,
+            self.serial,
+            self.sequence,
+            self.granule_pos,
+            0x04, // eos
+            &[],
+        );
+    }
+}
+
+/// a serial number identifying this client's Ogg logical bitstream; doesn't need to
+/// be cryptographically random, just distinct enough across concurrently open streams
+fn stream_serial() -> u32 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0x5357_5948) // falls back to "SWYH" if the clock is broken
+}
+
+/// linear resampler, interleaved stereo f32 in, interleaved stereo f32 out; shared
+/// with the WHEP feeder, which also has to get arbitrary source rates to 48kHz
+pub(crate) fn resample_stereo(input: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || input.is_empty() {
+        return input.to_vec();
+    }
+    let in_frames = input.len() / OPUS_CHANNELS;
+    let out_frames = (in_frames as u64 * to_rate as u64 / from_rate as u64) as usize;
+    let mut out = Vec::with_capacity(out_frames * OPUS_CHANNELS);
+    for i in 0..out_frames {
+        let src_pos = i as f64 * from_rate as f64 / to_rate as f64;
+        let idx = src_pos as usize;
+        let frac = (src_pos - idx as f64) as f32;
+        for ch in 0..OPUS_CHANNELS {
+            let a = input.get(idx * OPUS_CHANNELS + ch).copied().unwrap_or(0.0);
+            let b = input
+                .get((idx + 1) * OPUS_CHANNELS + ch)
+                .copied()
+                .unwrap_or(a);
+            out.push(a + (b - a) * frac);
+        }
+    }
+    out
+}
+
+/// builds the mandatory `OpusHead` identification packet (RFC 7845 section 5.1)
+fn build_opus_head() -> Vec<u8> {
+    let mut head = Vec::with_capacity(19);
+    head.extend_from_slice(b"OpusHead");
+    head.push(1); // version
+    head.push(OPUS_CHANNELS as u8);
+    head.extend_from_slice(&0u16.to_le_bytes()); // pre-skip
+    head.extend_from_slice(&OPUS_SAMPLE_RATE.to_le_bytes()); // original input sample rate
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    head.push(0); // channel mapping family
+    head
+}
+
+/// builds the mandatory `OpusTags` comment packet (RFC 7845 section 5.2)
+fn build_opus_tags() -> Vec<u8> {
+    let mut tags = Vec::new();
+    tags.extend_from_slice(b"OpusTags");
+    let vendor = b"swyh-rs";
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor);
+    tags.extend_from_slice(&0u32.to_le_bytes()); // no user comments
+    tags
+}
+
+/// writes a single Ogg page (no payload splitting across pages - our packets are
+/// always well under the 65025-byte single-page limit)
+fn write_ogg_page(
+    out: &mut VecDeque<u8>,
+    serial: u32,
+    sequence: u32,
+    granule_pos: i64,
+    header_type: u8,
+    payload: &[u8],
+) {
+    let mut segments = Vec::new();
+    let mut remaining = payload.len();
+    while remaining >= 255 {
+        segments.push(255u8);
+        remaining -= 255;
+    }
+    segments.push(remaining as u8);
+
+    let mut page = Vec::with_capacity(27 + segments.len() + payload.len());
+    page.extend_from_slice(b"OggS");
+    page.push(0); // version
+    page.push(header_type);
+    page.extend_from_slice(&granule_pos.to_le_bytes());
+    page.extend_from_slice(&serial.to_le_bytes());
+    page.extend_from_slice(&sequence.to_le_bytes());
+    page.extend_from_slice(&0u32.to_le_bytes()); // checksum placeholder, filled in below
+    page.push(segments.len() as u8);
+    page.extend_from_slice(&segments);
+    page.extend_from_slice(payload);
+
+    let crc = ogg_crc32(&page);
+    page[22..26].copy_from_slice(&crc.to_le_bytes());
+    out.extend(page);
+}
+
+/// the CRC-32 variant used by the Ogg container format: polynomial 0x04c11db7, no
+/// reflection, init 0, no final xor
+fn ogg_crc32(data: &[u8]) -> u32 {
+    static TABLE: std::sync::OnceLock<[u32; 256]> = std::sync::OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut crc = (i as u32) << 24;
+            for _ in 0..8 {
+                crc = if crc & 0x8000_0000 != 0 {
+                    (crc << 1) ^ 0x04c1_1db7
+                } else {
+                    crc << 1
+                };
+            }
+            *entry = crc;
+        }
+        table
+    });
+    let mut crc = 0u32;
+    for &byte in data {
+        crc = (crc << 8) ^ table[(((crc >> 24) ^ byte as u32) & 0xff) as usize];
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resample_stereo_is_a_no_op_when_the_rate_is_unchanged() {
+        let input = vec![0.1, -0.2, 0.3, -0.4];
+        assert_eq!(resample_stereo(&input, 48_000, 48_000), input);
+    }
+
+    #[test]
+    fn resample_stereo_scales_frame_count_with_the_rate_ratio() {
+        let input: Vec<f32> = (0..40).map(|i| i as f32 / 40.0).collect(); // 20 stereo frames
+        let out = resample_stereo(&input, 48_000, 24_000);
+        assert_eq!(out.len() / OPUS_CHANNELS, 10);
+    }
+
+    #[test]
+    fn ogg_page_has_the_magic_number_and_a_self_consistent_crc() {
+        let mut out = VecDeque::new();
+        write_ogg_page(&mut out, 0x1234_5678, 1, 0, 0x02, b"hello");
+        let page: Vec<u8> = out.into_iter().collect();
+        assert_eq!(&page[0..4], b"OggS");
+        let mut zeroed = page.clone();
+        zeroed[22..26].copy_from_slice(&0u32.to_le_bytes());
+        let crc = u32::from_le_bytes(page[22..26].try_into().unwrap());
+        assert_eq!(crc, ogg_crc32(&zeroed));
+    }
+}